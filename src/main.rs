@@ -1,100 +1,503 @@
+mod player;
+
 use anyhow::Result;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use rodio::{Decoder, OutputStream, Sink};
-use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc, time::Duration};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use player::{Command, PlaybackState, PlayerController, PlaylistEntry, RepeatMode};
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::Reader;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph},
     Terminal,
 };
+use walkdir::WalkDir;
+
+/// Extensions rodio can decode out of the box.
+const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "flac", "wav", "ogg", "m4a"];
+
+/// How far a single seek press jumps.
+const SEEK_STEP: Duration = Duration::from_secs(5);
+
+/// Where `w` saves the current playlist.
+const PLAYLIST_SAVE_PATH: &str = "playlist.m3u";
+
+/// Whether `path` looks like a song rodio can decode, based on its extension.
+fn supported_song(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            SUPPORTED_EXTENSIONS
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(ext))
+        })
+        .unwrap_or(false)
+}
+
+/// Format a duration as `MM:SS`.
+fn fmt_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+/// Playlist file formats `load_playlist`/`save_playlist` can read and write,
+/// selected by a path's extension.
+enum PlaylistFormat {
+    M3u,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("m3u") | Some("m3u8") => Some(Self::M3u),
+            Some("xspf") => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// Parse an M3U/M3U8 playlist: one path or URL per line, `#EXTINF` and other
+/// `#`-prefixed directives ignored. Relative entries are resolved against
+/// `base_dir` (the playlist file's own directory), matching how players
+/// conventionally interpret M3U paths.
+fn parse_m3u(contents: &str, base_dir: &Path) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| resolve_entry(line, base_dir))
+        .collect()
+}
+
+/// Resolve a playlist entry against `base_dir`, leaving URLs and
+/// already-absolute paths untouched.
+fn resolve_entry(raw: &str, base_dir: &Path) -> PathBuf {
+    let path = PathBuf::from(raw);
+    if raw.contains("://") || path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    }
+}
+
+/// Render a playlist as M3U, with an `#EXTINF` line wherever a duration is known.
+fn write_m3u(songs: &[Song]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for song in songs {
+        if let Some(secs) = song.meta.duration.map(|d| d.as_secs()) {
+            out.push_str(&format!("#EXTINF:{},{}\n", secs, song.display_name()));
+        }
+        out.push_str(&song.path.to_string_lossy());
+        out.push('\n');
+    }
+    out
+}
+
+/// Parse an XSPF playlist, pulling the path/URI out of each `<track><location>`.
+/// A `<location>` outside of a `<track>` (e.g. the playlist's own top-level
+/// location) is valid XSPF but isn't a track, so it's ignored.
+fn parse_xspf(contents: &str, base_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut reader = Reader::from_str(contents);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut paths = Vec::new();
+    let mut in_track = false;
+    let mut in_location = false;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            XmlEvent::Start(e) if e.name().as_ref() == b"track" => in_track = true,
+            XmlEvent::End(e) if e.name().as_ref() == b"track" => in_track = false,
+            XmlEvent::Start(e) if in_track && e.name().as_ref() == b"location" => {
+                in_location = true
+            }
+            XmlEvent::End(e) if e.name().as_ref() == b"location" => in_location = false,
+            XmlEvent::Text(text) if in_location => {
+                paths.push(location_to_path(&text.unescape()?, base_dir));
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(paths)
+}
+
+/// XSPF `<location>` entries are URIs; strip a `file://` prefix when present,
+/// then resolve a relative result against `base_dir` the same way `parse_m3u` does.
+fn location_to_path(location: &str, base_dir: &Path) -> PathBuf {
+    match location.strip_prefix("file://") {
+        Some(rest) => resolve_entry(rest, base_dir),
+        None => resolve_entry(location, base_dir),
+    }
+}
 
+/// Render a playlist as a minimal XSPF document.
+fn write_xspf(songs: &[Song]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for song in songs {
+        out.push_str("    <track>\n      <location>file://");
+        out.push_str(&xml_escape(&song.path.to_string_lossy()));
+        out.push_str("</location>\n    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Tag metadata read from a song file, each field falling back to `None` when
+/// the tag is missing or unreadable.
+#[derive(Clone, Default)]
+struct SongMeta {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<Duration>,
+}
+
+impl SongMeta {
+    /// Probe `path` for ID3/Vorbis/MP4 tags, returning an empty `SongMeta` if
+    /// the file can't be read. A readable file with no tags still gets its
+    /// `duration` from the audio properties.
+    fn load(path: &Path) -> Self {
+        let tagged_file = match lofty::Probe::open(path).and_then(|p| p.read()) {
+            Ok(f) => f,
+            Err(_) => return Self::default(),
+        };
+        let duration = Some(tagged_file.properties().duration());
+        let tag = tagged_file
+            .primary_tag()
+            .or_else(|| tagged_file.first_tag());
+        let (title, artist, album) = match tag {
+            Some(tag) => (
+                tag.title().map(|s| s.to_string()),
+                tag.artist().map(|s| s.to_string()),
+                tag.album().map(|s| s.to_string()),
+            ),
+            None => (None, None, None),
+        };
+        Self {
+            title,
+            artist,
+            album,
+            duration,
+        }
+    }
+}
+
+/// A song in the playlist: its file path plus whatever tag metadata was found.
+/// The engine thread plays from its own `PlaylistEntry` copies (path plus the
+/// tag duration), sent by `App::sync_playlist`.
+struct Song {
+    path: PathBuf,
+    meta: SongMeta,
+}
+
+impl Song {
+    fn load(path: PathBuf) -> Self {
+        let meta = SongMeta::load(&path);
+        Self { path, meta }
+    }
+
+    /// "Artist — Title (Album)", falling back to the filename piece by piece
+    /// as tags are missing.
+    fn display_name(&self) -> String {
+        match (&self.meta.artist, &self.meta.title) {
+            (Some(artist), Some(title)) => match &self.meta.album {
+                Some(album) => format!("{} — {} ({})", artist, title, album),
+                None => format!("{} — {}", artist, title),
+            },
+            (None, Some(title)) => title.clone(),
+            _ => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/// UI-side state: the playlist (with display metadata), the browsing cursor,
+/// and a mirror of the engine's latest [`player::StatusUpdate`]. All actual
+/// playback lives behind `controller` on its own thread.
 struct App {
-    songs: Vec<PathBuf>,
+    songs: Vec<Song>,
+    selected: Option<usize>,
+    list_state: ListState,
+    volume: f32,
+    repeat: RepeatMode,
+    shuffle: bool,
+    /// Whether the `/` search box is open and capturing input.
+    search_active: bool,
+    search_query: String,
+    /// Indices into `songs` that match `search_query`, best match first.
+    filtered: Vec<usize>,
+    controller: PlayerController,
+    // Mirrored from the latest `StatusUpdate`, for rendering.
+    state: PlaybackState,
     current_song: Option<usize>,
-    playing: bool,
-    sink: Option<Sink>,
-    stream: Option<OutputStream>,
-    stream_handle: Option<Arc<rodio::OutputStreamHandle>>,
+    elapsed: Duration,
+    total_duration: Option<Duration>,
 }
 
 impl App {
-    fn new() -> Result<Self> {
-        // Initialize audio stream at startup
-        let (stream, stream_handle) = OutputStream::try_default()?;
-        Ok(Self {
+    fn new() -> Self {
+        Self {
             songs: Vec::new(),
+            selected: None,
+            list_state: ListState::default(),
+            volume: 1.0,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+            search_active: false,
+            search_query: String::new(),
+            filtered: Vec::new(),
+            controller: PlayerController::spawn(),
+            state: PlaybackState::Stopped,
             current_song: None,
-            playing: false,
-            sink: None,
-            stream: Some(stream),
-            stream_handle: Some(Arc::new(stream_handle)),
-        })
+            elapsed: Duration::ZERO,
+            total_duration: None,
+        }
     }
 
-    fn play(&mut self) -> Result<()> {
-        if let Some(index) = self.current_song {
-            if let Some(song_path) = self.songs.get(index) {
-                // Stop current playback if any
-                if let Some(sink) = self.sink.take() {
-                    sink.stop();
-                }
+    /// Apply a status snapshot from the engine thread. Deliberately leaves
+    /// `selected`/`list_state` (the browse cursor) alone: the engine may have
+    /// auto-advanced to a track the user hasn't scrolled to, and `current_song`
+    /// is rendered independently, so there's no need to yank the cursor along.
+    fn apply_status(&mut self, update: player::StatusUpdate) {
+        self.state = update.state;
+        self.current_song = update.current;
+        self.elapsed = update.elapsed;
+        self.total_duration = update.total;
+    }
 
-                // Create new sink and start playback
-                if let Some(handle) = &self.stream_handle {
-                    let file = BufReader::new(File::open(song_path)?);
-                    let source = Decoder::new(file)?;
-                    let sink = Sink::try_new(&**handle)?;
-                    sink.append(source);
-                    sink.play();
-                    self.sink = Some(sink);
-                    self.playing = true;
-                }
-            }
+    /// Push the in-memory playlist to the engine, keeping indices in sync
+    /// with `songs` and passing along each song's tag-derived duration so the
+    /// progress gauge still works for formats (like MP3) whose decoder can't
+    /// report a total duration on its own.
+    fn sync_playlist(&self) {
+        let entries = self
+            .songs
+            .iter()
+            .map(|s| PlaylistEntry {
+                path: s.path.clone(),
+                duration_hint: s.meta.duration,
+            })
+            .collect();
+        self.controller.send(Command::SetPlaylist(entries));
+    }
+
+    fn cycle_repeat(&mut self) {
+        self.repeat = self.repeat.cycle();
+        self.controller.send(Command::SetRepeat(self.repeat));
+    }
+
+    fn toggle_shuffle(&mut self) {
+        self.shuffle = !self.shuffle;
+        self.controller.send(Command::SetShuffle(self.shuffle));
+    }
+
+    /// Indices of the songs currently visible in the playlist, in render order:
+    /// the fuzzy-filtered set while searching, otherwise every song.
+    fn active_order(&self) -> Vec<usize> {
+        if self.search_active {
+            self.filtered.clone()
+        } else {
+            (0..self.songs.len()).collect()
         }
-        Ok(())
     }
 
-    fn pause(&mut self) {
-        if let Some(sink) = &self.sink {
-            if self.playing {
-                sink.pause();
-            } else {
-                sink.play();
+    /// Point `selected`/`list_state` at `order[pos]`, falling back to the first
+    /// visible row if `selected` no longer appears in `order`.
+    fn sync_list_state(&mut self, order: &[usize]) {
+        if order.is_empty() {
+            self.selected = None;
+            self.list_state.select(None);
+            return;
+        }
+        match self
+            .selected
+            .and_then(|s| order.iter().position(|&i| i == s))
+        {
+            Some(pos) => self.list_state.select(Some(pos)),
+            None => {
+                self.selected = Some(order[0]);
+                self.list_state.select(Some(0));
             }
-            self.playing = !self.playing;
         }
     }
 
-    fn next_song(&mut self) {
-        if !self.songs.is_empty() {
-            self.current_song = Some(match self.current_song {
-                Some(current) => (current + 1) % self.songs.len(),
-                None => 0,
-            });
+    fn select_next(&mut self) {
+        let order = self.active_order();
+        if order.is_empty() {
+            return;
         }
+        let pos = self
+            .selected
+            .and_then(|s| order.iter().position(|&i| i == s));
+        let next_pos = match pos {
+            Some(p) => (p + 1) % order.len(),
+            None => 0,
+        };
+        self.selected = Some(order[next_pos]);
+        self.list_state.select(Some(next_pos));
     }
 
-    fn previous_song(&mut self) {
-        if !self.songs.is_empty() {
-            self.current_song = Some(match self.current_song {
-                Some(current) => (current + self.songs.len() - 1) % self.songs.len(),
-                None => 0,
-            });
+    fn select_previous(&mut self) {
+        let order = self.active_order();
+        if order.is_empty() {
+            return;
         }
+        let pos = self
+            .selected
+            .and_then(|s| order.iter().position(|&i| i == s));
+        let prev_pos = match pos {
+            Some(p) => (p + order.len() - 1) % order.len(),
+            None => 0,
+        };
+        self.selected = Some(order[prev_pos]);
+        self.list_state.select(Some(prev_pos));
+    }
+
+    /// Open the search box, ready to accept a fuzzy query.
+    fn enter_search(&mut self) {
+        self.search_active = true;
+        self.search_query.clear();
+        self.update_filter();
+    }
+
+    /// Close the search box and go back to browsing the full playlist.
+    fn exit_search(&mut self) {
+        self.search_active = false;
+        self.search_query.clear();
+        self.filtered.clear();
+    }
+
+    fn search_push_char(&mut self, c: char) {
+        self.search_query.push(c);
+        self.update_filter();
+    }
+
+    fn search_pop_char(&mut self) {
+        self.search_query.pop();
+        self.update_filter();
+    }
+
+    /// Re-rank `songs` against `search_query` and refresh `filtered`/the cursor.
+    fn update_filter(&mut self) {
+        if self.search_query.is_empty() {
+            self.filtered = (0..self.songs.len()).collect();
+        } else {
+            let matcher = SkimMatcherV2::default();
+            let mut scored: Vec<(i64, usize)> = self
+                .songs
+                .iter()
+                .enumerate()
+                .filter_map(|(i, song)| {
+                    matcher
+                        .fuzzy_match(&song.display_name(), &self.search_query)
+                        .map(|score| (score, i))
+                })
+                .collect();
+            scored.sort_by(|a, b| b.0.cmp(&a.0));
+            self.filtered = scored.into_iter().map(|(_, i)| i).collect();
+        }
+        let order = self.filtered.clone();
+        self.sync_list_state(&order);
+    }
+
+    fn play_selected(&mut self) {
+        if let Some(index) = self.selected {
+            self.controller.send(Command::Play(index));
+        }
+    }
+
+    /// Adjust the volume by `delta` (clamped to 0.0-1.0) and push it to the engine.
+    fn adjust_volume(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).clamp(0.0, 1.0);
+        self.controller.send(Command::SetVolume(self.volume));
+    }
+
+    /// Seek by `delta` seconds (positive forward, negative backward) from the
+    /// last known elapsed position.
+    fn seek(&mut self, delta: i64) {
+        let elapsed_secs = self.elapsed.as_secs() as i64;
+        let target_secs = (elapsed_secs + delta).max(0) as u64;
+        self.controller
+            .send(Command::Seek(Duration::from_secs(target_secs)));
     }
 
     fn select_first_song(&mut self) {
-        if !self.songs.is_empty() && self.current_song.is_none() {
-            self.current_song = Some(0);
+        if !self.songs.is_empty() && self.selected.is_none() {
+            self.selected = Some(0);
+            self.list_state.select(self.selected);
         }
     }
+
+    /// Recursively walk `root` and add every supported song found underneath it.
+    fn load_dir(&mut self, root: &Path) {
+        for entry in WalkDir::new(root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let path = entry.into_path();
+            if supported_song(&path) {
+                self.songs.push(Song::load(path));
+            }
+        }
+        self.sync_playlist();
+    }
+
+    /// Replace `songs` with the contents of an M3U/M3U8 or XSPF playlist file,
+    /// picked by `path`'s extension.
+    fn load_playlist(&mut self, path: &Path) -> Result<()> {
+        let format = PlaylistFormat::from_path(path)
+            .ok_or_else(|| anyhow::anyhow!("unrecognized playlist extension: {}", path.display()))?;
+        let contents = std::fs::read_to_string(path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let paths = match format {
+            PlaylistFormat::M3u => parse_m3u(&contents, base_dir),
+            PlaylistFormat::Xspf => parse_xspf(&contents, base_dir)?,
+        };
+        self.songs = paths.into_iter().map(Song::load).collect();
+        self.sync_playlist();
+        Ok(())
+    }
+
+    /// Write the current playlist out to `path` as M3U or XSPF, picked by its extension.
+    fn save_playlist(&self, path: &Path) -> Result<()> {
+        let format = PlaylistFormat::from_path(path)
+            .ok_or_else(|| anyhow::anyhow!("unrecognized playlist extension: {}", path.display()))?;
+        let contents = match format {
+            PlaylistFormat::M3u => write_m3u(&self.songs),
+            PlaylistFormat::Xspf => write_xspf(&self.songs),
+        };
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
 }
 
 fn main() -> Result<()> {
@@ -106,11 +509,19 @@ fn main() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app and run it
-    let mut app = App::new()?;
+    let mut app = App::new();
 
-    // Example: Add some songs to the playlist
-    app.songs.push(PathBuf::from("src/Mixdown_toska(6).mp3"));
-    app.songs.push(PathBuf::from("src/forlorad for alltid.mp3"));
+    // Load songs from the directory or playlist file given on the command
+    // line, or the current directory if none was given.
+    let root = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    if PlaylistFormat::from_path(&root).is_some() {
+        app.load_playlist(&root)?;
+    } else {
+        app.load_dir(&root);
+    }
     app.select_first_song();
 
     let res = run_app(&mut terminal, &mut app);
@@ -134,81 +545,290 @@ fn main() -> Result<()> {
 fn run_app<B: tui::backend::Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
     loop {
         terminal.draw(|f| {
+            let gauge_height = if app.total_duration.is_some() { 3 } else { 0 };
+            let search_height = if app.search_active { 3 } else { 0 };
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(1)
-                .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Length(search_height),
+                        Constraint::Length(gauge_height),
+                        Constraint::Min(0),
+                    ]
+                    .as_ref(),
+                )
                 .split(f.size());
 
             // Now Playing
-            let now_playing = if let Some(current) = app.current_song {
-                if let Some(path) = app.songs.get(current) {
-                    format!(
-                        "Now Playing: {} [{}]",
-                        path.file_name().unwrap().to_string_lossy(),
-                        if app.playing { "Playing" } else { "Paused" }
-                    )
-                } else {
-                    String::from("No song selected")
-                }
-            } else {
-                String::from("No song selected")
+            let modes = format!(
+                "Repeat: {} Shuffle: {}",
+                app.repeat.label(),
+                if app.shuffle { "On" } else { "Off" }
+            );
+            let now_playing = match app.current_song.and_then(|i| app.songs.get(i)) {
+                Some(song) => format!(
+                    "Now Playing: {} [{}] Vol: {}% {}",
+                    song.display_name(),
+                    app.state.label(),
+                    (app.volume * 100.0).round() as i32,
+                    modes
+                ),
+                None => format!(
+                    "No song selected Vol: {}% {}",
+                    (app.volume * 100.0).round() as i32,
+                    modes
+                ),
             };
 
             let current_status = Paragraph::new(now_playing)
                 .block(Block::default().borders(Borders::ALL).title("Status"));
             f.render_widget(current_status, chunks[0]);
 
-            // Playlist
+            // Search box (hidden unless `/` was pressed)
+            if app.search_active {
+                let search_box = Paragraph::new(format!("/{}", app.search_query))
+                    .block(Block::default().borders(Borders::ALL).title("Search"));
+                f.render_widget(search_box, chunks[1]);
+            }
+
+            // Now Playing progress gauge (hidden when the duration is unknown)
+            if let Some(total) = app.total_duration {
+                let elapsed = app.elapsed.min(total);
+                let ratio = if total.as_secs_f64() > 0.0 {
+                    (elapsed.as_secs_f64() / total.as_secs_f64()).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let gauge = Gauge::default()
+                    .block(Block::default().borders(Borders::ALL).title("Progress"))
+                    .gauge_style(Style::default().fg(Color::Cyan))
+                    .ratio(ratio)
+                    .label(format!(
+                        "{} / {}",
+                        fmt_duration(elapsed),
+                        fmt_duration(total)
+                    ));
+                f.render_widget(gauge, chunks[2]);
+            }
+
+            // Playlist (fuzzy-filtered while searching)
+            let matcher = SkimMatcherV2::default();
             let songs: Vec<ListItem> = app
-                .songs
-                .iter()
-                .enumerate()
-                .map(|(i, path)| {
-                    let content = if Some(i) == app.current_song {
-                        vec![Spans::from(vec![
-                            Span::raw("▶ "),
-                            Span::styled(
-                                path.file_name().unwrap().to_string_lossy(),
-                                Style::default().add_modifier(Modifier::BOLD),
-                            ),
-                        ])]
+                .active_order()
+                .into_iter()
+                .map(|i| {
+                    let song = &app.songs[i];
+                    let name = song.display_name();
+                    let matched: Vec<usize> = if app.search_active && !app.search_query.is_empty() {
+                        matcher
+                            .fuzzy_indices(&name, &app.search_query)
+                            .map(|(_, indices)| indices)
+                            .unwrap_or_default()
+                    } else {
+                        Vec::new()
+                    };
+
+                    let base_style = if Some(i) == app.current_song {
+                        Style::default().add_modifier(Modifier::BOLD)
                     } else {
-                        vec![Spans::from(vec![
-                            Span::raw("  "),
-                            Span::raw(path.file_name().unwrap().to_string_lossy()),
-                        ])]
+                        Style::default()
                     };
-                    ListItem::new(content)
+                    let mut spans = vec![Span::styled(
+                        if Some(i) == app.current_song {
+                            "▶ "
+                        } else {
+                            "  "
+                        },
+                        base_style,
+                    )];
+                    for (pos, ch) in name.chars().enumerate() {
+                        let style = if matched.contains(&pos) {
+                            base_style
+                                .fg(Color::Cyan)
+                                .add_modifier(Modifier::UNDERLINED)
+                        } else {
+                            base_style
+                        };
+                        spans.push(Span::styled(ch.to_string(), style));
+                    }
+                    ListItem::new(vec![Spans::from(spans)])
                 })
                 .collect();
 
             let songs = List::new(songs)
                 .block(Block::default().borders(Borders::ALL).title("Playlist"))
-                .highlight_style(Style::default().fg(Color::Yellow));
+                .highlight_style(Style::default().fg(Color::Yellow))
+                .highlight_symbol("> ");
 
-            f.render_widget(songs, chunks[1]);
+            f.render_stateful_widget(songs, chunks[3], &mut app.list_state);
         })?;
 
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => return Ok(()),
-                    KeyCode::Char(' ') => app.pause(),
-                    KeyCode::Right => {
-                        app.next_song();
-                        app.play()?;
-                    }
-                    KeyCode::Left => {
-                        app.previous_song();
-                        app.play()?;
+                if app.search_active {
+                    match key.code {
+                        KeyCode::Esc => app.exit_search(),
+                        KeyCode::Enter => {
+                            app.play_selected();
+                            app.exit_search();
+                        }
+                        KeyCode::Backspace => app.search_pop_char(),
+                        KeyCode::Down => app.select_next(),
+                        KeyCode::Up => app.select_previous(),
+                        KeyCode::Char(c) => app.search_push_char(c),
+                        _ => {}
                     }
-                    KeyCode::Enter => {
-                        app.play()?;
+                } else {
+                    match key.code {
+                        KeyCode::Char('q') => return Ok(()),
+                        KeyCode::Char(' ') => app.controller.send(Command::Pause),
+                        KeyCode::Char('x') => app.controller.send(Command::Stop),
+                        KeyCode::Char('/') => app.enter_search(),
+                        KeyCode::Down | KeyCode::Char('j') => app.select_next(),
+                        KeyCode::Up | KeyCode::Char('k') => app.select_previous(),
+                        KeyCode::Char('+') => app.adjust_volume(0.05),
+                        KeyCode::Char('-') => app.adjust_volume(-0.05),
+                        KeyCode::Char('.') => app.seek(SEEK_STEP.as_secs() as i64),
+                        KeyCode::Char(',') => app.seek(-(SEEK_STEP.as_secs() as i64)),
+                        KeyCode::Char('r') => app.cycle_repeat(),
+                        KeyCode::Char('s') => app.toggle_shuffle(),
+                        KeyCode::Char('w') => {
+                            app.save_playlist(Path::new(PLAYLIST_SAVE_PATH))?
+                        }
+                        KeyCode::Right => app.controller.send(Command::Next),
+                        KeyCode::Left => app.controller.send(Command::Prev),
+                        KeyCode::Enter => app.play_selected(),
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
         }
+
+        while let Ok(update) = app.controller.updates.try_recv() {
+            app.apply_status(update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_m3u_skips_blank_lines_and_directives() {
+        let contents = "#EXTM3U\n#EXTINF:123,Some Song\n/music/song.mp3\n\n  \nhttp://stream.example/radio\n";
+        let paths = parse_m3u(contents, Path::new("/playlists"));
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/song.mp3"),
+                PathBuf::from("http://stream.example/radio"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_m3u_resolves_relative_entries_against_base_dir() {
+        let contents = "song.mp3\n../other/song2.mp3\n";
+        let paths = parse_m3u(contents, Path::new("/music/playlists"));
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/playlists/song.mp3"),
+                PathBuf::from("/music/playlists/../other/song2.mp3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_m3u_includes_extinf_only_when_duration_known() {
+        let songs = vec![
+            Song {
+                path: PathBuf::from("/music/one.mp3"),
+                meta: SongMeta {
+                    title: Some("One".to_string()),
+                    duration: Some(Duration::from_secs(61)),
+                    ..Default::default()
+                },
+            },
+            Song {
+                path: PathBuf::from("/music/two.mp3"),
+                meta: SongMeta::default(),
+            },
+        ];
+        let out = write_m3u(&songs);
+        assert_eq!(
+            out,
+            "#EXTM3U\n#EXTINF:61,One\n/music/one.mp3\n/music/two.mp3\n"
+        );
+    }
+
+    #[test]
+    fn parse_xspf_extracts_locations_and_strips_file_scheme() {
+        let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track><location>file:///music/one.flac</location></track>
+    <track><location>http://stream.example/radio</location></track>
+  </trackList>
+</playlist>"#;
+        let paths = parse_xspf(contents, Path::new("/playlists")).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/music/one.flac"),
+                PathBuf::from("http://stream.example/radio"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_xspf_ignores_playlist_level_location() {
+        let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <location>file:///playlists/this-playlist.xspf</location>
+  <trackList>
+    <track><location>song.mp3</location></track>
+  </trackList>
+</playlist>"#;
+        let paths = parse_xspf(contents, Path::new("/music")).unwrap();
+        assert_eq!(paths, vec![PathBuf::from("/music/song.mp3")]);
+    }
+
+    #[test]
+    fn location_to_path_strips_file_scheme_and_resolves_relative() {
+        assert_eq!(
+            location_to_path("file:///music/one.flac", Path::new("/playlists")),
+            PathBuf::from("/music/one.flac")
+        );
+        assert_eq!(
+            location_to_path("http://stream.example/radio", Path::new("/playlists")),
+            PathBuf::from("http://stream.example/radio")
+        );
+        assert_eq!(
+            location_to_path("one.flac", Path::new("/music/playlists")),
+            PathBuf::from("/music/playlists/one.flac")
+        );
+    }
+
+    #[test]
+    fn xml_escape_escapes_reserved_characters() {
+        assert_eq!(xml_escape("a & b < c > d"), "a &amp; b &lt; c &gt; d");
+    }
+
+    #[test]
+    fn supported_song_checks_extension_case_insensitively() {
+        assert!(supported_song(Path::new("track.MP3")));
+        assert!(supported_song(Path::new("track.flac")));
+        assert!(!supported_song(Path::new("track.txt")));
+        assert!(!supported_song(Path::new("noext")));
+    }
+
+    #[test]
+    fn fmt_duration_formats_as_mm_ss() {
+        assert_eq!(fmt_duration(Duration::from_secs(65)), "01:05");
+        assert_eq!(fmt_duration(Duration::from_secs(3)), "00:03");
     }
 }
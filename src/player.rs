@@ -0,0 +1,628 @@
+//! The audio engine: owns the `OutputStream`/`Sink` and runs on its own
+//! thread, so playback and gapless preloading never block on UI rendering.
+//! Talk to it with [`Command`]s sent through [`PlayerController::send`] and
+//! read [`StatusUpdate`]s back from [`PlayerController::updates`].
+
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How the engine advances when a track finishes on its own.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RepeatMode {
+    Off,
+    All,
+    One,
+}
+
+impl RepeatMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RepeatMode::Off => "Off",
+            RepeatMode::All => "All",
+            RepeatMode::One => "One",
+        }
+    }
+
+    pub fn cycle(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+}
+
+/// One playlist entry as the engine sees it: a path to decode plus whatever
+/// duration the UI already knows from tags, used when the decoder itself
+/// can't report one (rodio's MP3 decoder never does).
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub duration_hint: Option<Duration>,
+}
+
+/// A request sent from the UI thread to the engine thread.
+pub enum Command {
+    /// Replace the playlist the engine plays from (the UI still keeps tag
+    /// metadata and display formatting on its own side).
+    SetPlaylist(Vec<PlaylistEntry>),
+    Play(usize),
+    /// Toggle between playing and paused.
+    Pause,
+    Stop,
+    SetVolume(f32),
+    /// Seek to an absolute position within the current track.
+    Seek(Duration),
+    /// Step to the next/previous track in playlist order, ignoring shuffle/repeat.
+    Next,
+    Prev,
+    SetShuffle(bool),
+    SetRepeat(RepeatMode),
+}
+
+/// Coarse playback state reported back to the UI.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl PlaybackState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PlaybackState::Stopped => "Stopped",
+            PlaybackState::Playing => "Playing",
+            PlaybackState::Paused => "Paused",
+        }
+    }
+}
+
+/// A snapshot of the engine's state, sent to the UI roughly every tick.
+pub struct StatusUpdate {
+    pub state: PlaybackState,
+    pub current: Option<usize>,
+    pub elapsed: Duration,
+    pub total: Option<Duration>,
+}
+
+/// How often the engine thread polls for commands, checks for a finished
+/// track, and reports a [`StatusUpdate`].
+const TICK: Duration = Duration::from_millis(50);
+
+/// Handle to the audio engine thread. The UI only ever touches this and the
+/// [`StatusUpdate`]s it receives back — never a `Sink` directly.
+pub struct PlayerController {
+    commands: Sender<Command>,
+    pub updates: Receiver<StatusUpdate>,
+}
+
+impl PlayerController {
+    /// Spawn the engine thread and return a handle to talk to it.
+    pub fn spawn() -> Self {
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (status_tx, status_rx) = mpsc::channel();
+        thread::spawn(move || run_engine(cmd_rx, status_tx));
+        Self {
+            commands: cmd_tx,
+            updates: status_rx,
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+/// State of the background decode for the upcoming track.
+enum Preload {
+    /// Decoding is in progress on a background thread.
+    Pending(usize, Receiver<Result<Decoder<BufReader<File>>>>),
+    /// Decoding finished and the source is ready to be appended to a fresh sink.
+    Ready(usize, Decoder<BufReader<File>>),
+}
+
+/// Tracks shuffle/repeat mode and decides which playlist index plays next.
+/// Deliberately free of any I/O (it only ever sees a playlist length and the
+/// current index, never the playlist itself) so the ordering logic can be
+/// unit tested without a real audio device.
+struct PlayOrder {
+    repeat: RepeatMode,
+    shuffle: bool,
+    /// Permutation of playlist indices used for playback order while `shuffle` is on.
+    shuffle_order: Vec<usize>,
+    /// Position of the current track within `shuffle_order`.
+    shuffle_pos: usize,
+}
+
+impl PlayOrder {
+    fn new() -> Self {
+        Self {
+            repeat: RepeatMode::Off,
+            shuffle: false,
+            shuffle_order: Vec::new(),
+            shuffle_pos: 0,
+        }
+    }
+
+    fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    fn set_shuffle(&mut self, shuffle: bool, len: usize, current: Option<usize>) {
+        self.shuffle = shuffle;
+        if shuffle {
+            self.reshuffle(len, current);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.shuffle_order.clear();
+        self.shuffle_pos = 0;
+    }
+
+    /// Plain wraparound step used by manual `Next`/`Prev`, independent of shuffle/repeat.
+    fn sequential_index(&self, current: Option<usize>, len: usize, step: i64) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        let current = current.map(|i| i as i64).unwrap_or(-1);
+        Some((current + step).rem_euclid(len as i64) as usize)
+    }
+
+    /// Recompute the shuffled playback order, keeping `shuffle_pos` pointed at the
+    /// currently playing track.
+    fn reshuffle(&mut self, len: usize, current: Option<usize>) {
+        let mut order: Vec<usize> = (0..len).collect();
+        order.shuffle(&mut rand::thread_rng());
+        self.shuffle_pos = current
+            .and_then(|current| order.iter().position(|&i| i == current))
+            .unwrap_or(0);
+        self.shuffle_order = order;
+    }
+
+    /// Keep `shuffle_order`/`shuffle_pos` pointed at whatever just started
+    /// playing, however it was picked (`Play`/`Next`/`Prev`), so the next
+    /// peek/advance continues from here instead of a stale position.
+    fn resync(&mut self, index: usize, len: usize) {
+        if self.shuffle && self.shuffle_order.len() == len {
+            if let Some(pos) = self.shuffle_order.iter().position(|&i| i == index) {
+                self.shuffle_pos = pos;
+            }
+        }
+    }
+
+    /// Pick the next track index to play when the current one finishes, honoring
+    /// the active shuffle/repeat settings, without mutating shuffle state.
+    /// `None` means playback would stop there.
+    fn peek_next_index(&self, current: Option<usize>, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        if self.repeat == RepeatMode::One {
+            return current;
+        }
+        if self.shuffle {
+            if self.shuffle_order.len() != len {
+                return None;
+            }
+            if self.shuffle_pos + 1 < self.shuffle_order.len() {
+                Some(self.shuffle_order[self.shuffle_pos + 1])
+            } else if self.repeat == RepeatMode::All {
+                self.shuffle_order.first().copied()
+            } else {
+                None
+            }
+        } else {
+            match current {
+                Some(current) if current + 1 < len => Some(current + 1),
+                Some(_) if self.repeat == RepeatMode::All => Some(0),
+                None => Some(0),
+                _ => None,
+            }
+        }
+    }
+
+    /// Pick the next track index to play when the current one finishes, honoring
+    /// the active shuffle/repeat settings, advancing shuffle state as it goes.
+    /// `None` means playback should stop.
+    fn next_on_end(&mut self, current: Option<usize>, len: usize) -> Option<usize> {
+        if self.shuffle && self.shuffle_order.len() != len {
+            self.reshuffle(len, current);
+        }
+        let next = self.peek_next_index(current, len);
+        if self.shuffle && self.repeat != RepeatMode::One {
+            if self.shuffle_pos + 1 < self.shuffle_order.len() {
+                self.shuffle_pos += 1;
+            } else if self.repeat == RepeatMode::All {
+                self.reshuffle(len, current);
+            }
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod play_order_tests {
+    use super::*;
+
+    #[test]
+    fn sequential_wraps_both_directions() {
+        let order = PlayOrder::new();
+        assert_eq!(order.sequential_index(None, 3, 1), Some(0));
+        assert_eq!(order.sequential_index(Some(2), 3, 1), Some(0));
+        assert_eq!(order.sequential_index(Some(0), 3, -1), Some(2));
+        assert_eq!(order.sequential_index(Some(1), 3, -1), Some(0));
+        assert_eq!(order.sequential_index(None, 0, 1), None);
+    }
+
+    #[test]
+    fn peek_next_sequential_stops_without_repeat() {
+        let order = PlayOrder::new();
+        assert_eq!(order.peek_next_index(Some(0), 3), Some(1));
+        assert_eq!(order.peek_next_index(Some(2), 3), None);
+    }
+
+    #[test]
+    fn peek_next_sequential_wraps_on_repeat_all() {
+        let mut order = PlayOrder::new();
+        order.set_repeat(RepeatMode::All);
+        assert_eq!(order.peek_next_index(Some(2), 3), Some(0));
+    }
+
+    #[test]
+    fn repeat_one_always_replays_current() {
+        let mut order = PlayOrder::new();
+        order.set_repeat(RepeatMode::One);
+        assert_eq!(order.peek_next_index(Some(1), 3), Some(1));
+        assert_eq!(order.next_on_end(Some(1), 3), Some(1));
+    }
+
+    #[test]
+    fn shuffle_iterates_the_generated_permutation_without_repeats() {
+        let mut order = PlayOrder::new();
+        order.set_shuffle(true, 5, None);
+        let mut seen = vec![order.shuffle_order[order.shuffle_pos]];
+        let mut current = seen[0];
+        for _ in 0..4 {
+            let next = order.next_on_end(Some(current), 5).unwrap();
+            assert!(!seen.contains(&next), "shuffle replayed {next} early");
+            seen.push(next);
+            current = next;
+        }
+        assert_eq!(order.next_on_end(Some(current), 5), None);
+    }
+
+    #[test]
+    fn resync_after_manual_pick_keeps_shuffle_from_repeating_or_skipping() {
+        let mut order = PlayOrder::new();
+        order.set_shuffle(true, 4, None);
+        // Manually jump to whatever comes right after the current position...
+        let picked = order.peek_next_index(Some(order.shuffle_order[order.shuffle_pos]), 4);
+        let picked = picked.unwrap();
+        // ...exactly like play() does: resync shuffle_pos to match, rather
+        // than leaving it pointed at the old position.
+        order.resync(picked, 4);
+        let expected = order.shuffle_order[(order.shuffle_order.iter().position(|&i| i == picked).unwrap() + 1) % 4];
+        assert_eq!(order.peek_next_index(Some(picked), 4), Some(expected));
+    }
+}
+
+/// The actual audio state, private to the engine thread.
+struct Engine {
+    playlist: Vec<PlaylistEntry>,
+    current: Option<usize>,
+    volume: f32,
+    order: PlayOrder,
+    /// Background decode of whatever `next_on_end` would pick next.
+    preload: Option<Preload>,
+    sink: Option<Sink>,
+    _stream: OutputStream,
+    stream_handle: OutputStreamHandle,
+    total_duration: Option<Duration>,
+    elapsed_accum: Duration,
+    leg_start: Option<Instant>,
+    playing: bool,
+}
+
+impl Engine {
+    fn new() -> Result<Self> {
+        let (stream, stream_handle) = OutputStream::try_default()?;
+        Ok(Self {
+            playlist: Vec::new(),
+            current: None,
+            volume: 1.0,
+            order: PlayOrder::new(),
+            preload: None,
+            sink: None,
+            _stream: stream,
+            stream_handle,
+            total_duration: None,
+            elapsed_accum: Duration::ZERO,
+            leg_start: None,
+            playing: false,
+        })
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::SetPlaylist(paths) => {
+                self.playlist = paths;
+                self.current = None;
+                self.order.reset();
+                self.preload = None;
+            }
+            Command::Play(index) => {
+                let _ = self.play(index);
+            }
+            Command::Pause => self.toggle_pause(),
+            Command::Stop => self.stop(),
+            Command::SetVolume(volume) => {
+                self.volume = volume.clamp(0.0, 1.0);
+                if let Some(sink) = &self.sink {
+                    sink.set_volume(self.volume);
+                }
+            }
+            Command::Seek(target) => {
+                let _ = self.seek(target);
+            }
+            Command::Next => {
+                if let Some(index) = self.order.sequential_index(self.current, self.playlist.len(), 1) {
+                    let _ = self.play(index);
+                }
+            }
+            Command::Prev => {
+                if let Some(index) = self.order.sequential_index(self.current, self.playlist.len(), -1) {
+                    let _ = self.play(index);
+                }
+            }
+            Command::SetShuffle(shuffle) => {
+                self.order.set_shuffle(shuffle, self.playlist.len(), self.current);
+                self.preload = None;
+                self.ensure_preload();
+            }
+            Command::SetRepeat(repeat) => {
+                self.order.set_repeat(repeat);
+                self.preload = None;
+                self.ensure_preload();
+            }
+        }
+    }
+
+    /// Start playing `index`, using the preloaded decode if it's ready and
+    /// matches, and kick off a preload of whatever plays after it.
+    fn play(&mut self, index: usize) -> Result<()> {
+        if self.playlist.get(index).is_none() {
+            return Ok(());
+        }
+        let preloaded = match &self.preload {
+            Some(Preload::Ready(i, _)) if *i == index => self.preload.take(),
+            _ => None,
+        };
+        self.current = Some(index);
+        // However this index was picked (Play/Next/Prev), keep the shuffle
+        // order pointed at it so the next peek/advance continues from here
+        // instead of a stale position.
+        self.order.resync(index, self.playlist.len());
+        let duration_hint = self.playlist[index].duration_hint;
+        match preloaded {
+            Some(Preload::Ready(_, source)) => self.start_sink(source, duration_hint)?,
+            _ => {
+                self.preload = None;
+                let file = BufReader::new(File::open(&self.playlist[index].path)?);
+                let source = Decoder::new(file)?;
+                self.start_sink(source, duration_hint)?;
+            }
+        }
+        self.ensure_preload();
+        Ok(())
+    }
+
+    /// Stop whatever is currently playing and start a fresh sink from `source`.
+    /// `duration_hint` is used when the decoder itself can't report a total
+    /// duration (rodio's MP3 decoder never does).
+    fn start_sink(
+        &mut self,
+        source: Decoder<BufReader<File>>,
+        duration_hint: Option<Duration>,
+    ) -> Result<()> {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self.total_duration = source.total_duration().or(duration_hint);
+        let sink = Sink::try_new(&self.stream_handle)?;
+        sink.set_volume(self.volume);
+        sink.append(source);
+        sink.play();
+        self.sink = Some(sink);
+        self.playing = true;
+        self.elapsed_accum = Duration::ZERO;
+        self.leg_start = Some(Instant::now());
+        Ok(())
+    }
+
+    fn toggle_pause(&mut self) {
+        if let Some(sink) = &self.sink {
+            if self.playing {
+                sink.pause();
+                if let Some(start) = self.leg_start.take() {
+                    self.elapsed_accum += start.elapsed();
+                }
+            } else {
+                sink.play();
+                self.leg_start = Some(Instant::now());
+            }
+            self.playing = !self.playing;
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self.sink = None;
+        self.playing = false;
+        self.leg_start = None;
+        self.elapsed_accum = Duration::ZERO;
+        self.total_duration = None;
+        self.current = None;
+        self.preload = None;
+    }
+
+    /// Elapsed playback time of the current track.
+    fn elapsed(&self) -> Duration {
+        self.elapsed_accum
+            + self
+                .leg_start
+                .map(|start| start.elapsed())
+                .unwrap_or_default()
+    }
+
+    /// Seek to an absolute `target` position by recreating the sink and
+    /// skipping the decoder to that position.
+    fn seek(&mut self, target: Duration) -> Result<()> {
+        let index = match self.current {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let path = match self.playlist.get(index) {
+            Some(entry) => entry.path.clone(),
+            None => return Ok(()),
+        };
+        let target = match self.total_duration {
+            Some(total) => target.min(total),
+            None => target,
+        };
+
+        let was_playing = self.playing;
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        let file = BufReader::new(File::open(&path)?);
+        let source = Decoder::new(file)?.skip_duration(target);
+        let sink = Sink::try_new(&self.stream_handle)?;
+        sink.set_volume(self.volume);
+        sink.append(source);
+        if was_playing {
+            sink.play();
+            self.leg_start = Some(Instant::now());
+        } else {
+            sink.pause();
+            self.leg_start = None;
+        }
+        self.sink = Some(sink);
+        self.playing = was_playing;
+        self.elapsed_accum = target;
+        Ok(())
+    }
+
+    /// Kick off a background decode of whatever track would play after this one,
+    /// unless it's already pending or ready.
+    fn ensure_preload(&mut self) {
+        let next_index = match self.order.peek_next_index(self.current, self.playlist.len()) {
+            Some(index) => index,
+            None => {
+                self.preload = None;
+                return;
+            }
+        };
+        let up_to_date = match &self.preload {
+            Some(Preload::Pending(i, _)) | Some(Preload::Ready(i, _)) => *i == next_index,
+            None => false,
+        };
+        if up_to_date {
+            return;
+        }
+        let path = match self.playlist.get(next_index) {
+            Some(entry) => entry.path.clone(),
+            None => return,
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let decoded = File::open(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| Decoder::new(BufReader::new(file)).map_err(anyhow::Error::from));
+            let _ = tx.send(decoded);
+        });
+        self.preload = Some(Preload::Pending(next_index, rx));
+    }
+
+    /// Pick up a finished background decode, if any.
+    fn poll_preload(&mut self) {
+        if let Some(Preload::Pending(index, rx)) = &self.preload {
+            if let Ok(decoded) = rx.try_recv() {
+                self.preload = match decoded {
+                    Ok(source) => Some(Preload::Ready(*index, source)),
+                    Err(_) => None,
+                };
+            }
+        }
+    }
+
+    /// Advance playback once the active sink has drained, per the current
+    /// shuffle/repeat mode. Uses the preloaded source when it matches.
+    fn advance_on_end(&mut self) -> Result<()> {
+        match self.order.next_on_end(self.current, self.playlist.len()) {
+            Some(index) => self.play(index),
+            None => {
+                self.stop();
+                Ok(())
+            }
+        }
+    }
+
+    fn status(&self) -> StatusUpdate {
+        let state = if self.sink.is_none() {
+            PlaybackState::Stopped
+        } else if self.playing {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::Paused
+        };
+        StatusUpdate {
+            state,
+            current: self.current,
+            elapsed: self.elapsed(),
+            total: self.total_duration,
+        }
+    }
+}
+
+/// The engine thread's body: drain a command (if any arrived within `TICK`),
+/// detect a naturally finished track, and report a fresh [`StatusUpdate`].
+/// This is what lets track-end detection and gapless preloading run
+/// independently of however long the UI takes to redraw.
+fn run_engine(commands: Receiver<Command>, status: Sender<StatusUpdate>) {
+    let mut engine = match Engine::new() {
+        Ok(engine) => engine,
+        Err(_) => return,
+    };
+    loop {
+        match commands.recv_timeout(TICK) {
+            Ok(command) => engine.handle(command),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+
+        engine.poll_preload();
+        if engine.playing {
+            if let Some(sink) = &engine.sink {
+                if sink.empty() {
+                    let _ = engine.advance_on_end();
+                }
+            }
+        }
+
+        if status.send(engine.status()).is_err() {
+            return;
+        }
+    }
+}